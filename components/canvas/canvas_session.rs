@@ -1,7 +1,7 @@
 use ferrite_session::*;
 
 use cssparser::RGBA;
-use euclid::default::{Point2D, Rect, Size2D, Transform2D};
+use euclid::default::{Point2D, Rect, Size2D, Transform2D, Vector2D};
 use ipc_channel::ipc::{self, IpcSharedMemory};
 use serde;
 use serde_bytes::ByteBuf;
@@ -16,6 +16,33 @@ use crate::canvas_paint_thread::{AntialiasMode, WebrenderApi};
 use canvas_traits::canvas::*;
 use gfx::font_cache_thread::FontCacheThread;
 
+/// Resampling filter used when `DrawImage` scales its source pixels.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub enum InterpolationMode {
+    NearestNeighbor,
+    Bilinear,
+    HighQuality,
+}
+
+/// Mirrors the HTML canvas `imageSmoothingQuality` property.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub enum ImageSmoothingQuality {
+    Low,
+    Medium,
+    High,
+}
+
+impl InterpolationMode {
+    /// Filter chosen for a given smoothing quality while smoothing is enabled.
+    fn from_quality(quality: ImageSmoothingQuality) -> InterpolationMode {
+        match quality {
+            ImageSmoothingQuality::Low => InterpolationMode::Bilinear,
+            ImageSmoothingQuality::Medium => InterpolationMode::Bilinear,
+            ImageSmoothingQuality::High => InterpolationMode::HighQuality,
+        }
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum CanvasMessage {
     Arc(Point2D<f32>, f32, f32, f32, bool),
@@ -52,8 +79,19 @@ pub enum CanvasMessage {
     SetFont(FontStyleStruct),
     SetTextAlign(TextAlign),
     SetTextBaseline(TextBaseline),
+    SetImageSmoothingQuality(ImageSmoothingQuality),
     PutImageData(Rect<u64>, IpcSharedMemory),
     Recreate(Size2D<u64>),
+    CreatePath(u64),
+    PathMoveTo(u64, Point2D<f32>),
+    PathLineTo(u64, Point2D<f32>),
+    PathBezierCurveTo(u64, Point2D<f32>, Point2D<f32>, Point2D<f32>),
+    PathArc(u64, Point2D<f32>, f32, f32, f32, bool),
+    PathClosePath(u64),
+    FillPath(u64, FillOrStrokeStyle, FillRule),
+    StrokePath(u64, FillOrStrokeStyle),
+    ClipPath(u64, FillRule),
+    DropPath(u64),
 }
 
 define_choice! { CanvasOps;
@@ -74,7 +112,7 @@ define_choice! { CanvasOps;
     Z
   >,
   IsPointInPath: ReceiveValue <
-    ( f64, f64, FillRule ),
+    ( f64, f64, FillRule, Option<u64> ),
     SendValue <
       bool,
       Z
@@ -95,8 +133,312 @@ pub type CanvasSession = LinearToShared<ExternalChoice<CanvasOps>>;
 pub type CreateCanvasSession =
     LinearToShared<ReceiveValue<(Size2D<u64>, bool), SendValue<SharedChannel<CanvasSession>, Z>>>;
 
-fn handle_canvas_message(canvas: &mut CanvasData<'static>, message: CanvasMessage) {
+/// Accumulates the device-space bounding rectangle of the drawing ops in a
+/// single batch so that, on flush, only the touched sub-region of the backing
+/// store needs to be re-uploaded to WebRender instead of the whole surface.
+///
+/// The tracker mirrors just enough context state (current transform, line and
+/// shadow parameters) to map each op's local bounds into device space. Ops that
+/// can touch the whole surface — a full-canvas `ClearRect`, a compositing mode
+/// that samples outside the painted region, or a `Recreate` — collapse the
+/// accumulator to the full canvas rect.
+struct DamageTracker {
+  canvas_rect: Rect<f32>,
+  transform: Transform2D<f32>,
+  transform_stack: Vec<Transform2D<f32>>,
+  path_bounds: Option<Rect<f32>>,
+  line_width: f32,
+  miter_limit: f32,
+  font_size: f32,
+  text_align: TextAlign,
+  text_baseline: TextBaseline,
+  shadow_offset_x: f32,
+  shadow_offset_y: f32,
+  shadow_blur: f32,
+  shadow_alpha: f32,
+  dirty: Option<Rect<f32>>,
+  full: bool,
+}
+
+impl DamageTracker {
+  /// Seed a tracker for a canvas of `size` with the canvas's current transform.
+  /// The remaining context state (line/shadow/font) starts at its defaults,
+  /// matching a freshly created canvas; once live it is carried across batches
+  /// rather than reset per flush, since the real context state is sticky.
+  fn new(size: Size2D<u64>, transform: Transform2D<f32>) -> DamageTracker {
+    DamageTracker {
+      canvas_rect: Rect::new(
+        Point2D::origin(),
+        Size2D::new(size.width as f32, size.height as f32),
+      ),
+      transform,
+      transform_stack: vec![],
+      path_bounds: None,
+      line_width: 1.0,
+      miter_limit: 10.0,
+      font_size: 10.0,
+      text_align: TextAlign::Start,
+      text_baseline: TextBaseline::Alphabetic,
+      shadow_offset_x: 0.0,
+      shadow_offset_y: 0.0,
+      shadow_blur: 0.0,
+      shadow_alpha: 0.0,
+      dirty: None,
+      full: false,
+    }
+  }
+
+  /// Dirty region accumulated since the last flush: `None` when nothing was
+  /// painted, the union rect clamped to the canvas otherwise (the full canvas
+  /// if a whole-surface op was seen). Consuming the region clears the
+  /// accumulator but leaves the sticky context state intact for later batches.
+  fn take(&mut self) -> Option<Rect<f32>> {
+    let full = self.full;
+    self.full = false;
+    if full {
+      self.dirty = None;
+      return Some(self.canvas_rect);
+    }
+    self.dirty.take().and_then(|rect| rect.intersection(&self.canvas_rect))
+  }
+
+  fn mark_full(&mut self) {
+    self.full = true;
+  }
+
+  fn union_device(&mut self, local: Rect<f32>, with_shadow: bool) {
+    let mut device = self.transform.outer_transformed_rect(&local);
+    if with_shadow && self.shadow_alpha > 0.0 {
+      let inflate = self.shadow_blur;
+      let shadow = device
+        .translate(Vector2D::new(self.shadow_offset_x, self.shadow_offset_y))
+        .inflate(inflate, inflate);
+      device = device.union(&shadow);
+    }
+    self.dirty = Some(match self.dirty {
+      Some(existing) => existing.union(&device),
+      None => device,
+    });
+  }
+
+  fn extend_path(&mut self, local: Rect<f32>) {
+    self.path_bounds = Some(match self.path_bounds {
+      Some(existing) => existing.union(&local),
+      None => local,
+    });
+  }
+
+  /// Bounding box of the current path, inflated by `extra` on each side (used to
+  /// account for stroke width and miter joins).
+  fn path_device(&self, extra: f32) -> Option<Rect<f32>> {
+    self.path_bounds.map(|bounds| bounds.inflate(extra, extra))
+  }
+
+  fn record(&mut self, message: &CanvasMessage) {
+    match message {
+      CanvasMessage::SaveContext => self.transform_stack.push(self.transform),
+      CanvasMessage::RestoreContext => {
+        if let Some(transform) = self.transform_stack.pop() {
+          self.transform = transform;
+        }
+      },
+      CanvasMessage::SetTransform(matrix) => self.transform = *matrix,
+      CanvasMessage::SetLineWidth(width) => self.line_width = *width,
+      CanvasMessage::SetMiterLimit(limit) => self.miter_limit = *limit,
+      CanvasMessage::SetShadowOffsetX(value) => self.shadow_offset_x = *value as f32,
+      CanvasMessage::SetShadowOffsetY(value) => self.shadow_offset_y = *value as f32,
+      CanvasMessage::SetShadowBlur(value) => self.shadow_blur = *value as f32,
+      CanvasMessage::SetShadowColor(color) => self.shadow_alpha = color.alpha as f32,
+      CanvasMessage::SetFont(font) => {
+        self.font_size = font.font_size.computed_size().px();
+      },
+      CanvasMessage::SetTextAlign(align) => self.text_align = align.clone(),
+      CanvasMessage::SetTextBaseline(baseline) => self.text_baseline = baseline.clone(),
+      CanvasMessage::SetGlobalComposition(op) => {
+        if composition_touches_whole_surface(op) {
+          self.mark_full();
+        }
+      },
+      CanvasMessage::BeginPath => self.path_bounds = None,
+      CanvasMessage::MoveTo(point) | CanvasMessage::LineTo(point) => {
+        self.extend_path(Rect::new(*point, Size2D::zero()));
+      },
+      CanvasMessage::Rect(rect) => self.extend_path(*rect),
+      CanvasMessage::QuadraticCurveTo(cp, pt) => {
+        self.extend_path(bounds_of(&[*cp, *pt]));
+      },
+      CanvasMessage::BezierCurveTo(cp1, cp2, pt) => {
+        self.extend_path(bounds_of(&[*cp1, *cp2, *pt]));
+      },
+      CanvasMessage::Arc(center, radius, ..) => {
+        self.extend_path(circle_bounds(*center, *radius));
+      },
+      CanvasMessage::ArcTo(cp1, cp2, radius) => {
+        self.extend_path(bounds_of(&[*cp1, *cp2]).inflate(*radius, *radius));
+      },
+      CanvasMessage::Ellipse(center, radius_x, radius_y, ..) => {
+        let radius = radius_x.max(*radius_y);
+        self.extend_path(circle_bounds(*center, radius));
+      },
+      CanvasMessage::FillRect(rect, _) => self.union_device(*rect, true),
+      CanvasMessage::StrokeRect(rect, _) => {
+        let extra = self.line_width / 2.0 * self.miter_limit.max(1.0);
+        self.union_device(rect.inflate(extra, extra), true);
+      },
+      CanvasMessage::Fill(_) => {
+        if let Some(bounds) = self.path_device(0.0) {
+          self.union_device(bounds, true);
+        }
+      },
+      CanvasMessage::Stroke(_) => {
+        let extra = self.line_width / 2.0 * self.miter_limit.max(1.0);
+        if let Some(bounds) = self.path_device(extra) {
+          self.union_device(bounds, true);
+        }
+      },
+      CanvasMessage::ClearRect(rect) => {
+        if self.transform == Transform2D::identity()
+          && rect.contains_rect(&self.canvas_rect)
+        {
+          self.mark_full();
+        } else {
+          self.union_device(*rect, false);
+        }
+      },
+      CanvasMessage::FillText(text, x, y, max_width, _, is_rtl) => {
+        // Conservative text extents: a full em per glyph bounds even CJK/
+        // full-width runs, and an ascent of one em with a half-em descent
+        // covers the vertical run. The box is placed from the active
+        // `textAlign`/`textBaseline` so right/centered/non-alphabetic draws are
+        // contained rather than reported to the opposite side of `(x, y)`.
+        let width = max_width
+          .map(|w| w as f32)
+          .unwrap_or_else(|| text.chars().count() as f32 * self.font_size);
+        let ascent = self.font_size;
+        let descent = self.font_size * 0.5;
+        let height = ascent + descent;
+        let left = match resolve_text_align(&self.text_align, *is_rtl) {
+          HorizontalAnchor::Left => *x as f32,
+          HorizontalAnchor::Center => *x as f32 - width / 2.0,
+          HorizontalAnchor::Right => *x as f32 - width,
+        };
+        let top = match self.text_baseline {
+          TextBaseline::Top | TextBaseline::Hanging => *y as f32,
+          TextBaseline::Middle => *y as f32 - height / 2.0,
+          TextBaseline::Bottom | TextBaseline::Ideographic => *y as f32 - height,
+          _ => *y as f32 - ascent,
+        };
+        self.union_device(
+          Rect::new(Point2D::new(left, top), Size2D::new(width, height)),
+          true,
+        );
+      },
+      CanvasMessage::DrawImage(_, _, dest_rect, ..) => {
+        self.union_device(dest_rect.to_f32(), true);
+      },
+      CanvasMessage::PutImageData(rect, _) => {
+        // Put is addressed directly in device space, bypassing the transform.
+        self.dirty = Some(match self.dirty {
+          Some(existing) => existing.union(&rect.to_f32()),
+          None => rect.to_f32(),
+        });
+      },
+      CanvasMessage::Recreate(size) => {
+        self.canvas_rect = Rect::new(
+          Point2D::origin(),
+          Size2D::new(size.width as f32, size.height as f32),
+        );
+        self.transform = Transform2D::identity();
+        self.transform_stack.clear();
+        self.mark_full();
+      },
+      // `FillPath`/`StrokePath` damage is unioned in `handle_canvas_message`,
+      // where the registry's cached bounds for the stored path are available
+      // (see `union_stored_path`). `ClipPath` paints nothing.
+      _ => {},
+    }
+  }
+
+  /// Union the bounds of a stored path (in user space) into the dirty region,
+  /// inflating by the stroke allowance when the path is being stroked.
+  fn union_stored_path(&mut self, bounds: Rect<f32>, stroke: bool) {
+    let extra = if stroke {
+      self.line_width / 2.0 * self.miter_limit.max(1.0)
+    } else {
+      0.0
+    };
+    self.union_device(bounds.inflate(extra, extra), true);
+  }
+}
+
+fn bounds_of(points: &[Point2D<f32>]) -> Rect<f32> {
+  let mut iter = points.iter();
+  let first = iter.next().copied().unwrap_or_else(Point2D::origin);
+  let mut rect = Rect::new(first, Size2D::zero());
+  for point in iter {
+    rect = rect.union(&Rect::new(*point, Size2D::zero()));
+  }
+  rect
+}
+
+fn circle_bounds(center: Point2D<f32>, radius: f32) -> Rect<f32> {
+  Rect::new(
+    Point2D::new(center.x - radius, center.y - radius),
+    Size2D::new(radius * 2.0, radius * 2.0),
+  )
+}
+
+/// Side of the anchor point `(x, y)` that a text run occupies.
+enum HorizontalAnchor {
+  Left,
+  Center,
+  Right,
+}
+
+/// Resolve `textAlign` to a concrete side, using the run's direction for the
+/// direction-relative `start`/`end` values.
+fn resolve_text_align(align: &TextAlign, is_rtl: bool) -> HorizontalAnchor {
+  match align {
+    TextAlign::Left => HorizontalAnchor::Left,
+    TextAlign::Right => HorizontalAnchor::Right,
+    TextAlign::Center => HorizontalAnchor::Center,
+    TextAlign::Start => {
+      if is_rtl {
+        HorizontalAnchor::Right
+      } else {
+        HorizontalAnchor::Left
+      }
+    },
+    TextAlign::End => {
+      if is_rtl {
+        HorizontalAnchor::Left
+      } else {
+        HorizontalAnchor::Right
+      }
+    },
+  }
+}
+
+/// Whether a compositing mode can affect pixels outside the painted region, so
+/// that a batch using it has to re-upload the whole surface.
+fn composition_touches_whole_surface(op: &CompositionOrBlending) -> bool {
+  matches!(
+    op,
+    CompositionOrBlending::Composition(CompositionStyle::SourceIn)
+      | CompositionOrBlending::Composition(CompositionStyle::SourceOut)
+      | CompositionOrBlending::Composition(CompositionStyle::DestinationIn)
+      | CompositionOrBlending::Composition(CompositionStyle::DestinationAtop)
+      | CompositionOrBlending::Composition(CompositionStyle::Copy)
+  )
+}
+
+fn handle_canvas_message(
+  canvas: &mut CanvasData<'static>,
+  message: CanvasMessage,
+  damage: &mut DamageTracker,
+) {
   info!("handling CanvasMessage {:?}", message);
+  damage.record(&message);
   match message {
     CanvasMessage::FillText(text, x, y, max_width, style, is_rtl) => {
       canvas.set_fill_style(style);
@@ -137,12 +479,19 @@ fn handle_canvas_message(canvas: &mut CanvasData<'static>, message: CanvasMessag
             || vec![0; image_size.width as usize * image_size.height as usize * 4],
             |bytes| bytes.into_vec(),
         );
+        // Disabling smoothing forces nearest-neighbor regardless of the
+        // requested quality; otherwise the stored quality picks the filter.
+        let interpolation = if smoothing_enabled {
+            InterpolationMode::from_quality(canvas.image_smoothing_quality())
+        } else {
+            InterpolationMode::NearestNeighbor
+        };
         canvas.draw_image(
             data,
             image_size,
             dest_rect,
             source_rect,
-            smoothing_enabled,
+            interpolation,
         )
     },
     CanvasMessage::MoveTo(ref point) => canvas.move_to(point),
@@ -189,6 +538,9 @@ fn handle_canvas_message(canvas: &mut CanvasData<'static>, message: CanvasMessag
     CanvasMessage::SetTextBaseline(text_baseline) => {
         canvas.set_text_baseline(text_baseline)
     },
+    CanvasMessage::SetImageSmoothingQuality(quality) => {
+        canvas.set_image_smoothing_quality(quality)
+    },
     CanvasMessage::PutImageData(rect, img) => {
         info!("PutImageData");
         canvas.put_image_data(img.to_vec(), rect);
@@ -196,18 +548,50 @@ fn handle_canvas_message(canvas: &mut CanvasData<'static>, message: CanvasMessag
     CanvasMessage::Recreate(size) => {
         canvas.recreate(size);
     },
+    CanvasMessage::CreatePath(id) => canvas.create_path(id),
+    CanvasMessage::PathMoveTo(id, ref point) => canvas.path_move_to(id, point),
+    CanvasMessage::PathLineTo(id, ref point) => canvas.path_line_to(id, point),
+    CanvasMessage::PathBezierCurveTo(id, ref cp1, ref cp2, ref pt) => {
+        canvas.path_bezier_curve_to(id, cp1, cp2, pt)
+    },
+    CanvasMessage::PathArc(id, ref center, radius, start, end, ccw) => {
+        canvas.path_arc(id, center, radius, start, end, ccw)
+    },
+    CanvasMessage::PathClosePath(id) => canvas.path_close_path(id),
+    CanvasMessage::FillPath(id, style, fill_rule) => {
+        if let Some(bounds) = canvas.path_bounds(id) {
+            damage.union_stored_path(bounds, false);
+        }
+        canvas.set_fill_style(style);
+        canvas.fill_path(id, fill_rule);
+    },
+    CanvasMessage::StrokePath(id, style) => {
+        if let Some(bounds) = canvas.path_bounds(id) {
+            damage.union_stored_path(bounds, true);
+        }
+        canvas.set_stroke_style(style);
+        canvas.stroke_path(id);
+    },
+    CanvasMessage::ClipPath(id, fill_rule) => canvas.clip_path(id, fill_rule),
+    CanvasMessage::DropPath(id) => canvas.drop_path(id),
   }
 
   info!("done handling CanvasMessage");
 }
 
-pub fn canvas_session(mut canvas: CanvasData<'static>) -> SharedSession<CanvasSession> {
+pub fn canvas_session(
+    mut canvas: CanvasData<'static>,
+    mut damage: DamageTracker,
+) -> SharedSession<CanvasSession> {
     accept_shared_session(offer_choice! {
       Message => {
         receive_value! ( message => {
-          handle_canvas_message (&mut canvas, message);
+          handle_canvas_message (&mut canvas, message, &mut damage);
+          if let Some(dirty) = damage.take() {
+            canvas.update_webrender_image(dirty);
+          }
           detach_shared_session (
-            canvas_session ( canvas )
+            canvas_session ( canvas, damage )
           )
         })
       },
@@ -215,11 +599,14 @@ pub fn canvas_session(mut canvas: CanvasData<'static>) -> SharedSession<CanvasSe
         receive_value! ( messages => {
           info!("handling CanvasMessages {:?}", messages);
           for message in messages {
-            handle_canvas_message (&mut canvas, message);
+            handle_canvas_message (&mut canvas, message, &mut damage);
+          }
+          if let Some(dirty) = damage.take() {
+            canvas.update_webrender_image(dirty);
           }
 
           detach_shared_session (
-            canvas_session ( canvas )
+            canvas_session ( canvas, damage )
           )
         })
       },
@@ -228,7 +615,7 @@ pub fn canvas_session(mut canvas: CanvasData<'static>) -> SharedSession<CanvasSe
         let transform = canvas.get_transform();
         send_value! ( transform,
           detach_shared_session (
-            canvas_session ( canvas )
+            canvas_session ( canvas, damage )
           ))
       },
       GetImageData => {
@@ -239,19 +626,19 @@ pub fn canvas_session(mut canvas: CanvasData<'static>) -> SharedSession<CanvasSe
           sender.send(&pixels).unwrap();
 
           detach_shared_session (
-            canvas_session ( canvas )
+            canvas_session ( canvas, damage )
           )
         })
       },
       IsPointInPath => {
         info!("IsPointInPath");
         receive_value!( msg => {
-          let (x, y, fill_rule) = msg;
-          let res = canvas.is_point_in_path_bool(x, y, fill_rule);
+          let (x, y, fill_rule, path_id) = msg;
+          let res = canvas.is_point_in_path_bool(x, y, fill_rule, path_id);
 
           send_value!(res,
             detach_shared_session (
-              canvas_session ( canvas )
+              canvas_session ( canvas, damage )
             ))
         })
       },
@@ -259,7 +646,7 @@ pub fn canvas_session(mut canvas: CanvasData<'static>) -> SharedSession<CanvasSe
         info!("FromLayout");
         send_value ( canvas.get_data(),
           detach_shared_session (
-            canvas_session ( canvas )
+            canvas_session ( canvas, damage )
           ))
       },
       FromScript => {
@@ -268,7 +655,7 @@ pub fn canvas_session(mut canvas: CanvasData<'static>) -> SharedSession<CanvasSe
           canvas.send_pixels(sender);
 
           detach_shared_session (
-            canvas_session ( canvas )
+            canvas_session ( canvas, damage )
           )
         })
       },
@@ -297,8 +684,10 @@ pub fn run_create_canvas_session(ctx: CanvasContext) -> SharedSession<CreateCanv
         ctx.font_cache_thread.clone(),
       );
 
+      let damage = DamageTracker::new(size, canvas.get_transform());
+
       let (session, _) = run_shared_session (
-        canvas_session ( canvas )
+        canvas_session ( canvas, damage )
       );
 
       send_value! ( session,
@@ -322,49 +711,6 @@ pub fn create_canvas_session(
     channel
 }
 
-// pub async fn draw_image_in_other(
-//     source: SharedChannel<CanvasSession>,
-//     target: SharedChannel<CanvasSession>,
-//     image_size: Size2D<f64>,
-//     dest_rect: Rect<f64>,
-//     source_rect: Rect<f64>,
-//     smoothing: bool,
-// ) {
-//     debug!("[draw_image_in_other] acquiring shared session");
-
-//     run_session(acquire_shared_session!(source, source_chan =>
-//     choose!(
-//         source_chan,
-//         GetImageData,
-//         send_value_to!(
-//             source_chan,
-//             (source_rect.to_u64(), image_size.to_u64()),
-//             receive_value_from(source_chan, move | image: IpcSharedMemory | async move {
-//                 release_shared_session(
-//                     source_chan,
-//                     acquire_shared_session!(target, target_chan =>
-//                         choose!(
-//                             target_chan,
-//                             Message,
-//                             send_value_to!(
-//                                 target_chan,
-//                                 CanvasMessage::DrawImage(
-//                                     Some(ByteBuf::from(image.to_vec())),
-//                                     source_rect.size,
-//                                     dest_rect,
-//                                     source_rect,
-//                                     smoothing
-//                                 ),
-//                                 release_shared_session(target_chan, terminate())
-//                             ))))
-//             }))
-//                         )
-//                         ))
-//     .await;
-
-//     debug!("released shared session");
-// }
-
 lazy_static! {
   pub static ref RUNTIME : runtime::Runtime =
     runtime::Builder::new_multi_thread()
@@ -383,16 +729,144 @@ enum QueueItem {
   > > ),
 }
 
+// Once the buffer reaches this many messages a flush fires immediately rather
+// than waiting for the timer, so bursty drawing doesn't sit in the queue.
+const FLUSH_THRESHOLD: usize = 1024;
+
 #[derive(Clone)]
 pub struct AsyncQueue {
+  session: SharedChannel < CanvasSession >,
+  size: Size2D<u64>,
   task_sender:
     mpsc::UnboundedSender < QueueItem >
 }
 
+/// Discriminant for the idempotent, last-wins context setters: within a run of
+/// messages not broken by a drawing op or `SaveContext`/`RestoreContext`, only
+/// the final write of each of these affects the output.
+fn setter_key(message: &CanvasMessage) -> Option<u8> {
+  Some(match message {
+    CanvasMessage::SetLineWidth(..) => 0,
+    CanvasMessage::SetLineCap(..) => 1,
+    CanvasMessage::SetLineJoin(..) => 2,
+    CanvasMessage::SetMiterLimit(..) => 3,
+    CanvasMessage::SetGlobalAlpha(..) => 4,
+    CanvasMessage::SetGlobalComposition(..) => 5,
+    CanvasMessage::SetTransform(..) => 6,
+    CanvasMessage::SetShadowOffsetX(..) => 7,
+    CanvasMessage::SetShadowOffsetY(..) => 8,
+    CanvasMessage::SetShadowBlur(..) => 9,
+    CanvasMessage::SetShadowColor(..) => 10,
+    CanvasMessage::SetFont(..) => 11,
+    CanvasMessage::SetTextAlign(..) => 12,
+    CanvasMessage::SetTextBaseline(..) => 13,
+    CanvasMessage::SetImageSmoothingQuality(..) => 14,
+    _ => return None,
+  })
+}
+
+// Painting ops whose output is wiped by a later full-canvas `ClearRect`.
+fn is_overpaintable(message: &CanvasMessage) -> bool {
+  matches!(
+    message,
+    CanvasMessage::FillRect(..)
+      | CanvasMessage::StrokeRect(..)
+      | CanvasMessage::Fill(..)
+      | CanvasMessage::Stroke(..)
+      | CanvasMessage::FillText(..)
+      | CanvasMessage::DrawImage(..)
+      | CanvasMessage::PutImageData(..)
+      | CanvasMessage::ClearRect(..)
+      | CanvasMessage::FillPath(..)
+      | CanvasMessage::StrokePath(..)
+  )
+}
+
+fn is_full_canvas_clear(message: &CanvasMessage, canvas_size: Size2D<u64>) -> bool {
+  if let CanvasMessage::ClearRect(rect) = message {
+    rect.min_x() <= 0.0
+      && rect.min_y() <= 0.0
+      && rect.max_x() >= canvas_size.width as f32
+      && rect.max_y() >= canvas_size.height as f32
+  } else {
+    false
+  }
+}
+
+/// Shrink a buffered batch before it is sent: collapse redundant state setters
+/// to their last value, and drop painting ops that a later full-canvas
+/// `ClearRect` in the same batch erases anyway.
+fn coalesce_messages(
+  messages: Vec<CanvasMessage>,
+  canvas_size: Size2D<u64>,
+) -> Vec<CanvasMessage> {
+  use std::collections::HashMap;
+
+  let mut keep = vec![true; messages.len()];
+
+  let mut span = 0usize;
+  let mut last_of: HashMap<(usize, u8), usize> = HashMap::new();
+  for (i, message) in messages.iter().enumerate() {
+    match setter_key(message) {
+      Some(key) => {
+        if let Some(prev) = last_of.insert((span, key), i) {
+          keep[prev] = false;
+        }
+      },
+      None => span += 1,
+    }
+  }
+
+  // A `ClearRect` only wipes the whole surface when it covers the canvas under
+  // an identity transform; otherwise it clears a shifted region and earlier
+  // paint outside it must be kept. We can only prove the transform within this
+  // batch, so a clear is trusted as full-canvas only once an explicit
+  // `SetTransform(identity)` is in effect (consistent with `DamageTracker`).
+  let mut transform: Option<Transform2D<f32>> = None;
+  let mut transform_stack: Vec<Option<Transform2D<f32>>> = vec![];
+  let mut clear_at: Option<usize> = None;
+  for (i, message) in messages.iter().enumerate() {
+    match message {
+      CanvasMessage::SetTransform(matrix) => transform = Some(*matrix),
+      CanvasMessage::SaveContext => transform_stack.push(transform),
+      CanvasMessage::RestoreContext => {
+        // Restoring a save from an earlier batch brings back an unknown
+        // transform.
+        transform = transform_stack.pop().unwrap_or(None);
+      },
+      _ => {},
+    }
+    if transform == Some(Transform2D::identity())
+      && is_full_canvas_clear(message, canvas_size)
+    {
+      clear_at = Some(i);
+    }
+  }
+
+  if let Some(clear_at) = clear_at {
+    for (i, message) in messages.iter().enumerate().take(clear_at) {
+      if is_overpaintable(message) {
+        keep[i] = false;
+      }
+    }
+  }
+
+  messages
+    .into_iter()
+    .zip(keep)
+    .filter_map(|(message, keep)| keep.then_some(message))
+    .collect()
+}
+
 fn send_canvas_messages (
   session: SharedChannel < CanvasSession >,
   messages: Vec < CanvasMessage >,
+  canvas_size: Size2D<u64>,
 ) {
+  let messages = coalesce_messages(messages, canvas_size);
+  if messages.is_empty() {
+    return;
+  }
   async_acquire_shared_session ( session, move | chan | async move {
       choose! ( chan, Messages,
           send_value_to! ( chan, messages,
@@ -402,9 +876,10 @@ fn send_canvas_messages (
 }
 
 impl AsyncQueue {
-    pub fn new(session: SharedChannel<CanvasSession>)
+    pub fn new(session: SharedChannel<CanvasSession>, size: Size2D<u64>)
       -> AsyncQueue
     {
+        let queue_session = session.clone();
         let (sender, mut receiver) = mpsc::unbounded_channel();
         let mut messages: Vec<CanvasMessage> = vec![];
 
@@ -415,12 +890,20 @@ impl AsyncQueue {
                       match item {
                         QueueItem::Message(message) => {
                           messages.push(message);
+                          if messages.len() >= FLUSH_THRESHOLD {
+                            send_canvas_messages(
+                              session.clone(),
+                              messages.split_off(0),
+                              size
+                            );
+                          }
                         },
                         QueueItem::Yield => {
                           if ! messages.is_empty() {
                             send_canvas_messages(
                               session.clone(),
-                              messages.split_off(0)
+                              messages.split_off(0),
+                              size
                             );
                           }
                         },
@@ -428,7 +911,8 @@ impl AsyncQueue {
                           if ! messages.is_empty() {
                             send_canvas_messages(
                               session.clone(),
-                              messages.split_off(0)
+                              messages.split_off(0),
+                              size
                             );
                           }
 
@@ -453,14 +937,95 @@ impl AsyncQueue {
         });
 
         AsyncQueue {
+            session: queue_session,
+            size,
             task_sender: sender
         }
     }
 
+    /// Copy a sub-rectangle of pixels from another canvas session directly into
+    /// this one, without routing the data back through the script thread.
+    ///
+    /// The copy runs as a single queued job: the `source` session is acquired
+    /// with `GetImageData` to pull `src_rect` out of its backing store, then the
+    /// target is acquired and a `DrawImage` is issued under `composite_op` (and,
+    /// when given, clipped to `clip_rect`). Because it flows through
+    /// `enqueue_task`, it serializes after any drawing messages already buffered
+    /// for this queue.
+    pub fn draw_from(
+        &self,
+        source: SharedChannel<CanvasSession>,
+        source_size: Size2D<u64>,
+        src_rect: Rect<f64>,
+        dest_rect: Rect<f64>,
+        composite_op: CompositionOrBlending,
+        clip_rect: Option<Rect<f32>>,
+        smoothing_enabled: bool,
+    ) -> task::JoinHandle<()> {
+        let target = self.session.clone();
+        self.enqueue_task(move || async move {
+            let (pixel_sender, pixel_receiver) = ipc::bytes_channel().unwrap();
+
+            run_session(acquire_shared_session!(source, source_chan =>
+                choose!(source_chan, GetImageData,
+                    send_value_to!(source_chan,
+                        (src_rect.to_u64(), source_size, pixel_sender),
+                        release_shared_session(source_chan, terminate!())))))
+                .await;
+
+            let pixels = pixel_receiver.recv().unwrap();
+
+            let mut messages = vec![
+                CanvasMessage::SaveContext,
+                CanvasMessage::SetGlobalComposition(composite_op),
+            ];
+            if let Some(clip) = clip_rect {
+                messages.push(CanvasMessage::BeginPath);
+                messages.push(CanvasMessage::Rect(clip));
+                messages.push(CanvasMessage::Clip);
+            }
+            // `GetImageData` returns only the `src_rect` sub-region, packed
+            // with its origin at (0, 0), so the source rect into that buffer is
+            // the whole buffer — carrying `src_rect`'s offset would index past
+            // the readback.
+            messages.push(CanvasMessage::DrawImage(
+                Some(ByteBuf::from(pixels)),
+                src_rect.size,
+                dest_rect,
+                Rect::new(Point2D::origin(), src_rect.size),
+                smoothing_enabled,
+            ));
+            messages.push(CanvasMessage::RestoreContext);
+
+            run_session(acquire_shared_session!(target, target_chan =>
+                choose!(target_chan, Messages,
+                    send_value_to!(target_chan, messages,
+                        release_shared_session(target_chan, terminate!())))))
+                .await;
+        })
+    }
+
     pub fn send_canvas_message (&self, message: CanvasMessage) {
       self.task_sender.send(QueueItem::Message(message)).ok().unwrap();
     }
 
+    /// Read back a sub-rectangle of the canvas as raw RGBA pixels, serialized
+    /// after any drawing already buffered on this queue (it flows through
+    /// `enqueue_task`, which flushes the buffer first).
+    pub fn get_image_data(&self, rect: Rect<u64>) -> task::JoinHandle<Vec<u8>> {
+        let session = self.session.clone();
+        let size = self.size;
+        self.enqueue_task(move || async move {
+            let (sender, receiver) = ipc::bytes_channel().unwrap();
+            run_session(acquire_shared_session!(session, chan =>
+                choose!(chan, GetImageData,
+                    send_value_to!(chan, (rect, size, sender),
+                        release_shared_session(chan, terminate!())))))
+                .await;
+            receiver.recv().unwrap()
+        })
+    }
+
     pub fn enqueue_task <T, Fut> (
         &self,
         task: impl FnOnce() -> Fut
@@ -0,0 +1,175 @@
+//! Optional Unix-domain socket front-end for the canvas backend.
+//!
+//! This publishes `CreateCanvasSession` to processes outside Servo: a client
+//! opens a drawing surface over the socket, streams `CanvasMessage`s, and reads
+//! pixels back, much like a panel client talking to a compositor daemon. Each
+//! external handle is backed by a `SharedChannel<CanvasSession>` minted from
+//! `create_canvas_session` and driven through the same `AsyncQueue` batching as
+//! the in-process path, so external clients get identical flushing behaviour.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use euclid::default::{Rect, Size2D};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::oneshot;
+
+use ferrite_session::*;
+
+use crate::canvas_session::{
+    AsyncQueue, CanvasMessage, CanvasSession, CreateCanvasSession,
+};
+
+/// Opaque per-connection identifier for a canvas opened over the socket.
+pub type CanvasHandle = u64;
+
+/// Upper bound on a single framed payload. The socket is exposed to untrusted
+/// peers, so a hostile length prefix must not be able to force an unbounded
+/// allocation; frames larger than this are rejected before any buffer is sized.
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    CreateCanvas { size: Size2D<u64>, antialias: bool },
+    Send(CanvasHandle, Vec<CanvasMessage>),
+    GetImageData(CanvasHandle, Rect<u64>),
+    Close(CanvasHandle),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Response {
+    Created(CanvasHandle),
+    Ack,
+    ImageData(ByteBuf),
+    Error(String),
+}
+
+/// Bind `path` and serve canvas sessions until the listener errors.
+///
+/// `create` is the channel returned by `create_canvas_session`; each accepted
+/// connection gets its own handle namespace and runs concurrently.
+pub async fn serve(
+    path: impl AsRef<Path>,
+    create: SharedChannel<CreateCanvasSession>,
+) -> io::Result<()> {
+    let listener = UnixListener::bind(path)?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let create = create.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, create).await {
+                warn!("canvas socket connection closed: {}", err);
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    mut stream: UnixStream,
+    create: SharedChannel<CreateCanvasSession>,
+) -> io::Result<()> {
+    let mut handles: HashMap<CanvasHandle, AsyncQueue> = HashMap::new();
+    let mut next_handle: CanvasHandle = 0;
+
+    while let Some(frame) = read_frame(&mut stream).await? {
+        let request: Request = match bincode::deserialize(&frame) {
+            Ok(request) => request,
+            Err(err) => {
+                write_response(&mut stream, &Response::Error(err.to_string())).await?;
+                continue;
+            },
+        };
+
+        let response = match request {
+            Request::CreateCanvas { size, antialias } => {
+                let queue = create_canvas(&create, size, antialias).await;
+                let handle = next_handle;
+                next_handle += 1;
+                handles.insert(handle, queue);
+                Response::Created(handle)
+            },
+            Request::Send(handle, messages) => match handles.get(&handle) {
+                Some(queue) => {
+                    for message in messages {
+                        queue.send_canvas_message(message);
+                    }
+                    Response::Ack
+                },
+                None => Response::Error(format!("unknown canvas handle {}", handle)),
+            },
+            Request::GetImageData(handle, rect) => match handles.get(&handle) {
+                Some(queue) => {
+                    let pixels = queue.get_image_data(rect).await.unwrap_or_default();
+                    Response::ImageData(ByteBuf::from(pixels))
+                },
+                None => Response::Error(format!("unknown canvas handle {}", handle)),
+            },
+            Request::Close(handle) => {
+                if handles.remove(&handle).is_some() {
+                    Response::Ack
+                } else {
+                    Response::Error(format!("unknown canvas handle {}", handle))
+                }
+            },
+        };
+
+        write_response(&mut stream, &response).await?;
+    }
+
+    Ok(())
+}
+
+async fn create_canvas(
+    create: &SharedChannel<CreateCanvasSession>,
+    size: Size2D<u64>,
+    antialias: bool,
+) -> AsyncQueue {
+    let (sender, receiver) = oneshot::channel();
+
+    run_session(acquire_shared_session!(create.clone(), chan =>
+        send_value_to!(chan, (size, antialias),
+            receive_value_from(chan, move |session: SharedChannel<CanvasSession>| async move {
+                let _ = sender.send(session);
+                release_shared_session(chan, terminate!())
+            }))))
+        .await;
+
+    let session = receiver.await.expect("create_canvas session never delivered");
+    AsyncQueue::new(session, size)
+}
+
+async fn read_frame(stream: &mut UnixStream) -> io::Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 4];
+    match stream.read_exact(&mut len_buf).await {
+        Ok(_) => {},
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame length {} exceeds maximum of {}", len, MAX_FRAME_LEN),
+        ));
+    }
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await?;
+    Ok(Some(buf))
+}
+
+async fn write_response(stream: &mut UnixStream, response: &Response) -> io::Result<()> {
+    let payload = bincode::serialize(response)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    write_frame(stream, &payload).await
+}
+
+async fn write_frame(stream: &mut UnixStream, payload: &[u8]) -> io::Result<()> {
+    stream.write_all(&(payload.len() as u32).to_be_bytes()).await?;
+    stream.write_all(payload).await?;
+    stream.flush().await
+}